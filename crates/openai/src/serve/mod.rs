@@ -0,0 +1,18 @@
+//! HTTP serving layer: the ChatGPT-API reverse proxy plus the built-in
+//! playground/arena UI and gateway-token administration sitting in front of
+//! it.
+
+mod proxy;
+mod token_manager;
+mod web;
+
+use axum::Router;
+
+/// Router for the built-in playground/arena UI and the gateway token admin
+/// endpoint. The proxy's own routes are not included; the caller mounts
+/// them.
+pub fn router() -> Router {
+    Router::new()
+        .merge(web::router())
+        .merge(proxy::gateway_token_router())
+}
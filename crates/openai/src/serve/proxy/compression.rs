@@ -0,0 +1,204 @@
+//! gzip/brotli negotiation for the proxy response path.
+//!
+//! Relays an upstream response, re-compressing its body to match what the
+//! client asked for in `Accept-Encoding`. Live SSE bodies, and responses
+//! that already arrive in the encoding the client wants, are passed through
+//! untouched rather than buffered. `toapi` does its own JSON
+//! parsing/re-serialization on the upstream body and is responsible for
+//! decompressing it itself; this module only covers the plain relay path of
+//! `SendRequestExt::send_request` in [`super::req`].
+
+use std::io::Read;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use bytes::Bytes;
+use flate2::read::{GzDecoder, GzEncoder};
+use flate2::Compression;
+
+use super::ext::ResponseExt;
+use crate::serve::error::ResponseError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ContentEncoding {
+    Gzip,
+    Brotli,
+}
+
+impl ContentEncoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+}
+
+/// What an upstream response's `Content-Encoding` tells us, distinguishing
+/// "not compressed" from "compressed with something we don't decode" so the
+/// two are never conflated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpstreamEncoding {
+    None,
+    Known(ContentEncoding),
+    /// e.g. `deflate`: we can't decompress it, so it must be relayed as-is
+    /// rather than mislabeled.
+    Unsupported,
+}
+
+/// Relay an upstream response, decompressing and re-compressing its body
+/// only when the encodings actually differ. SSE bodies, responses already
+/// in the encoding the client wants, and bodies compressed with something
+/// we don't know how to decode are all passed through untouched rather than
+/// buffered.
+pub(super) async fn relay_with_compression(
+    resp: reqwest::Response,
+    accept_encoding: Option<&str>,
+) -> Result<ResponseExt, ResponseError> {
+    if is_event_stream(resp.headers()) {
+        return Ok(ResponseExt::builder().inner(resp).build());
+    }
+
+    let upstream_encoding = upstream_encoding_of(resp.headers());
+    let target_encoding = negotiate(accept_encoding);
+
+    let upstream_encoding = match upstream_encoding {
+        // We can't decode this, so recompressing it would corrupt the body;
+        // relay it exactly as the upstream sent it.
+        UpstreamEncoding::Unsupported => return Ok(ResponseExt::builder().inner(resp).build()),
+        UpstreamEncoding::Known(encoding) => Some(encoding),
+        UpstreamEncoding::None => None,
+    };
+    if upstream_encoding == target_encoding {
+        return Ok(ResponseExt::builder().inner(resp).build());
+    }
+
+    let status = resp.status();
+    let mut headers = resp.headers().clone();
+    let body = resp.bytes().await.map_err(ResponseError::InternalServerError)?;
+    let body = compress(target_encoding, decompress(upstream_encoding, body)?)?;
+
+    headers.remove(header::CONTENT_ENCODING);
+    headers.remove(header::CONTENT_LENGTH);
+    if let Some(encoding) = target_encoding {
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+    }
+
+    Ok(ResponseExt::builder()
+        .status(status)
+        .headers(headers)
+        .body(body)
+        .build())
+}
+
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|ct| ct.starts_with("text/event-stream"))
+        .unwrap_or(false)
+}
+
+fn upstream_encoding_of(headers: &HeaderMap) -> UpstreamEncoding {
+    match headers.get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok()) {
+        None => UpstreamEncoding::None,
+        Some("gzip") => UpstreamEncoding::Known(ContentEncoding::Gzip),
+        Some("br") => UpstreamEncoding::Known(ContentEncoding::Brotli),
+        Some(_) => UpstreamEncoding::Unsupported,
+    }
+}
+
+/// Pick the best encoding the client declared support for, preferring
+/// brotli over gzip when both are offered.
+fn negotiate(accept_encoding: Option<&str>) -> Option<ContentEncoding> {
+    let accept_encoding = accept_encoding?;
+    if accept_encoding.contains("br") {
+        Some(ContentEncoding::Brotli)
+    } else if accept_encoding.contains("gzip") {
+        Some(ContentEncoding::Gzip)
+    } else {
+        None
+    }
+}
+
+fn decompress(encoding: Option<ContentEncoding>, body: Bytes) -> Result<Bytes, ResponseError> {
+    match encoding {
+        None => Ok(body),
+        Some(ContentEncoding::Gzip) => {
+            let mut decoder = GzDecoder::new(body.as_ref());
+            let mut out = Vec::new();
+            decoder
+                .read_to_end(&mut out)
+                .map_err(ResponseError::InternalServerError)?;
+            Ok(Bytes::from(out))
+        }
+        Some(ContentEncoding::Brotli) => {
+            let mut out = Vec::new();
+            brotli::BrotliDecompress(&mut body.as_ref(), &mut out)
+                .map_err(ResponseError::InternalServerError)?;
+            Ok(Bytes::from(out))
+        }
+    }
+}
+
+fn compress(encoding: Option<ContentEncoding>, body: Bytes) -> Result<Bytes, ResponseError> {
+    match encoding {
+        None => Ok(body),
+        Some(ContentEncoding::Gzip) => {
+            let mut encoder = GzEncoder::new(body.as_ref(), Compression::fast());
+            let mut out = Vec::new();
+            encoder
+                .read_to_end(&mut out)
+                .map_err(ResponseError::InternalServerError)?;
+            Ok(Bytes::from(out))
+        }
+        Some(ContentEncoding::Brotli) => {
+            let mut out = Vec::new();
+            let params = brotli::enc::BrotliEncoderParams::default();
+            brotli::BrotliCompress(&mut body.as_ref(), &mut out, &params)
+                .map_err(ResponseError::InternalServerError)?;
+            Ok(Bytes::from(out))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_roundtrips() {
+        let body = Bytes::from_static(b"hello ninja, compress me please");
+        let compressed = compress(Some(ContentEncoding::Gzip), body.clone()).unwrap();
+        assert_ne!(compressed, body);
+        let decompressed = decompress(Some(ContentEncoding::Gzip), compressed).unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn brotli_roundtrips() {
+        let body = Bytes::from_static(b"hello ninja, compress me please");
+        let compressed = compress(Some(ContentEncoding::Brotli), body.clone()).unwrap();
+        assert_ne!(compressed, body);
+        let decompressed = decompress(Some(ContentEncoding::Brotli), compressed).unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    #[test]
+    fn no_encoding_is_a_no_op() {
+        let body = Bytes::from_static(b"pass through untouched");
+        assert_eq!(compress(None, body.clone()).unwrap(), body);
+        assert_eq!(decompress(None, body.clone()).unwrap(), body);
+    }
+
+    #[test]
+    fn unrecognized_encoding_is_unsupported_not_none() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("deflate"));
+        assert_eq!(upstream_encoding_of(&headers), UpstreamEncoding::Unsupported);
+
+        assert_eq!(upstream_encoding_of(&HeaderMap::new()), UpstreamEncoding::None);
+    }
+}
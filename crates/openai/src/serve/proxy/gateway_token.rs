@@ -0,0 +1,143 @@
+//! Gateway access-control tokens.
+//!
+//! Lets operators put ninja behind its own short-lived bearer tokens, sent in
+//! the `ninja-gateway-token` header rather than `Authorization`, that are
+//! independent of the upstream ChatGPT credential: a minting endpoint
+//! issues signed JWTs carrying an expiry and per-token feature flags (e.g.
+//! "allow-gpt4", "allow-dashboard"), and [`authorize`] enforces them at the
+//! top of [`super::req`]'s `send_request` before any upstream work happens.
+
+use axum::http::HeaderMap;
+use axum::routing::post;
+use axum::{Json, Router};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::with_context;
+
+const GATEWAY_TOKEN_HEADER: &str = "ninja-gateway-token";
+const GATEWAY_ADMIN_HEADER: &str = "ninja-gateway-admin-secret";
+
+/// Claims carried by a minted gateway token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GatewayClaims {
+    pub exp: i64,
+    #[serde(default)]
+    pub allow_gpt4: bool,
+    #[serde(default)]
+    pub allow_dashboard: bool,
+}
+
+impl GatewayClaims {
+    /// Claims used when gateway tokens aren't configured for this
+    /// deployment, so existing setups that never opted into the feature
+    /// keep working exactly as before.
+    fn unrestricted() -> Self {
+        Self {
+            exp: i64::MAX,
+            allow_gpt4: true,
+            allow_dashboard: true,
+        }
+    }
+}
+
+/// Request body for the minting endpoint.
+#[derive(Debug, Deserialize)]
+pub struct MintRequest {
+    /// Seconds from now the token should remain valid for.
+    pub ttl_secs: i64,
+    #[serde(default)]
+    pub allow_gpt4: bool,
+    #[serde(default)]
+    pub allow_dashboard: bool,
+}
+
+/// Mint a signed gateway token using the configured secret.
+pub fn mint(request: MintRequest) -> Result<String, ResponseError> {
+    let secret = with_context!(gateway_secret)
+        .ok_or(ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    let now = chrono::Utc::now().timestamp();
+    let claims = GatewayClaims {
+        exp: now + request.ttl_secs,
+        allow_gpt4: request.allow_gpt4,
+        allow_dashboard: request.allow_dashboard,
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(ResponseError::InternalServerError)
+}
+
+/// Verify and decode the gateway token attached to an inbound request.
+///
+/// Gateway tokens are opt-in: if no `gateway_secret` is configured, the
+/// feature is considered disabled and every request is treated as
+/// unrestricted, so deployments that never set one up aren't broken by this
+/// check. Once a secret is configured, a missing or invalid token returns
+/// `Unauthorized`.
+pub fn authorize(headers: &HeaderMap) -> Result<GatewayClaims, ResponseError> {
+    let Some(secret) = with_context!(gateway_secret) else {
+        return Ok(GatewayClaims::unrestricted());
+    };
+
+    let token = headers
+        .get(GATEWAY_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    let data = decode::<GatewayClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &Validation::default(),
+    )
+    .map_err(|_| ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    Ok(data.claims)
+}
+
+/// Router for the minting endpoint, mounted in front of the proxy so
+/// operators can issue gateway tokens without touching the upstream
+/// credential.
+pub fn router() -> Router {
+    Router::new().route("/gateway/tokens", post(mint_handler))
+}
+
+#[derive(Serialize)]
+struct MintResponse {
+    token: String,
+}
+
+/// `/gateway/tokens` mints operator-facing credentials, so it is itself
+/// gated behind a separate `gateway_admin_secret`, sent in the
+/// `ninja-gateway-admin-secret` header, rather than being open to anyone
+/// who can reach the listener; without that secret configured the
+/// minting endpoint is disabled entirely (not "accept anything").
+async fn mint_handler(
+    headers: HeaderMap,
+    Json(request): Json<MintRequest>,
+) -> Result<Json<MintResponse>, ResponseError> {
+    authorize_minting(&headers)?;
+    Ok(Json(MintResponse {
+        token: mint(request)?,
+    }))
+}
+
+fn authorize_minting(headers: &HeaderMap) -> Result<(), ResponseError> {
+    let admin_secret = with_context!(gateway_admin_secret)
+        .ok_or(ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    let provided = headers
+        .get(GATEWAY_ADMIN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    if provided != admin_secret {
+        return Err(ResponseError::Unauthorized(ProxyError::AccessTokenRequired));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+//! The proxy layer: request rewriting, upstream selection, and everything
+//! `send_request` (in [`req`]) leans on to turn an inbound request into an
+//! outbound one OpenAI will accept.
+
+pub(super) mod ext;
+mod req;
+pub(super) mod toapi;
+
+mod compression;
+mod gateway_token;
+mod sentinel;
+mod upstream;
+
+pub use ext::{header_convert, RequestExt, ResponseExt, SendRequestExt};
+pub use gateway_token::router as gateway_token_router;
@@ -14,11 +14,16 @@ use crate::constant::{ARKOSE_TOKEN, EMPTY, MODEL, NULL, PUID};
 use crate::gpt_model::GPTModel;
 use crate::{arkose, debug, warn, with_context};
 
+use super::compression::relay_with_compression;
 use super::ext::{RequestExt, ResponseExt, SendRequestExt};
+use super::gateway_token::{self, GatewayClaims};
 use super::header_convert;
+use super::sentinel::{self, ProofOfWork};
 use super::toapi;
+use super::upstream::is_failover_response;
 use crate::serve::error::{ProxyError, ResponseError};
 use crate::serve::puid::{get_or_init, reduce_key};
+use crate::serve::token_manager::TokenManager;
 use crate::URL_CHATGPT_API;
 
 #[async_trait]
@@ -28,8 +33,18 @@ impl SendRequestExt for reqwest::Client {
         origin: &'static str,
         mut req: RequestExt,
     ) -> Result<ResponseExt, ResponseError> {
-        // If to_api is true, then send request to api
+        // Enforce the gateway access-control token before any upstream work
+        // happens; this is independent of the upstream ChatGPT credential.
+        let gateway_claims = gateway_token::authorize(&req.headers)?;
+
+        // If to_api is true, then send request to api. The gpt-4 flag must
+        // be enforced here too: this branch returns before
+        // `handle_conv_request` ever runs, so a non-gpt4 gateway token could
+        // otherwise reach gpt-4 through the OpenAI-compatible endpoint.
         if toapi::support(&req) {
+            if let Some(model) = peek_request_model(&req) {
+                enforce_gpt4_flag(model, &gateway_claims)?;
+            }
             return toapi::send_request(req).await;
         }
 
@@ -40,28 +55,106 @@ impl SendRequestExt for reqwest::Client {
             .map(|v| v.as_str())
             .unwrap_or(req.uri.path());
 
-        // Build url
-        let url = format!("{origin}{path_and_query}");
-
         // Handle conversation request
-        handle_conv_request(&mut req).await?;
+        handle_conv_request(&mut req, &gateway_claims).await?;
 
         // Handle dashboard request
-        handle_dashboard_request(&mut req).await?;
-
-        // Build request
-        let mut builder =
-            self.request(req.method, url)
-                .headers(header_convert(&req.headers, &req.jar, origin)?);
-        if let Some(body) = req.body {
-            builder = builder.body(body);
+        handle_dashboard_request(&mut req, &gateway_claims).await?;
+
+        // Pick an upstream from the configured pool, falling back to the
+        // caller-supplied origin when no pool is configured, and retry the
+        // next healthy upstream on a connect error, 5xx or Cloudflare
+        // challenge response. Upstream origins are owned `String`s (they're
+        // deserialized from config at runtime), so candidates are too.
+        let pool = with_context!(upstream_pool);
+        let mut candidates: Vec<String> = match pool.and_then(|pool| pool.select()) {
+            Some(first) => {
+                let mut origins = vec![first.origin.clone()];
+                if let Some(pool) = pool {
+                    origins.extend(
+                        pool.fallbacks(&first.origin)
+                            .into_iter()
+                            .map(|u| u.origin.clone()),
+                    );
+                }
+                origins
+            }
+            None => vec![origin.to_owned()],
+        };
+        // Always keep the caller-supplied origin as the last resort.
+        if !candidates.iter().any(|candidate| candidate.as_str() == origin) {
+            candidates.push(origin.to_owned());
         }
 
-        // Send request
-        Ok(ResponseExt::builder().inner(builder.send().await?).build())
+        let mut last_err = None;
+        for candidate in &candidates {
+            let url = format!("{candidate}{path_and_query}");
+
+            // Dial through this upstream's configured proxy when it has
+            // one; otherwise reuse the shared client.
+            let client = pool
+                .and_then(|pool| pool.client_for(candidate))
+                .unwrap_or_else(|| self.clone());
+
+            let mut builder = client
+                .request(req.method.clone(), url)
+                .headers(header_convert(&req.headers, &req.jar, candidate)?);
+            if let Some(body) = req.body.clone() {
+                builder = builder.body(body);
+            }
+
+            match builder.send().await {
+                Ok(resp) => {
+                    if is_failover_response(resp.status(), resp.headers()) {
+                        if let Some(pool) = pool {
+                            pool.mark_unhealthy(candidate);
+                        }
+                        last_err = Some(ResponseError::InternalServerError(
+                            ProxyError::UpstreamUnavailable,
+                        ));
+                        continue;
+                    }
+                    let accept_encoding = req
+                        .headers
+                        .get(header::ACCEPT_ENCODING)
+                        .and_then(|v| v.to_str().ok());
+                    return relay_with_compression(resp, accept_encoding).await;
+                }
+                Err(err) => {
+                    if let Some(pool) = pool {
+                        pool.mark_unhealthy(candidate);
+                    }
+                    warn!("Upstream {candidate} failed, trying next: {err}");
+                    last_err = Some(ResponseError::from(err));
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or(ResponseError::InternalServerError(
+            ProxyError::UpstreamUnavailable,
+        )))
     }
 }
 
+/// Best-effort peek at the `model` field of a JSON request body, used to
+/// enforce gateway flags on paths (like `toapi`) that don't otherwise parse
+/// the body before dispatching upstream.
+fn peek_request_model(req: &RequestExt) -> Option<GPTModel> {
+    let body = req.body.as_ref()?;
+    let json = serde_json::from_slice::<Value>(body).ok()?;
+    let model_str = json.get(MODEL)?.as_str()?;
+    GPTModel::from_str(model_str).ok()
+}
+
+/// Reject gpt-4 models for gateway tokens that weren't minted with the
+/// "allow-gpt4" flag.
+fn enforce_gpt4_flag(model: GPTModel, gateway_claims: &GatewayClaims) -> Result<(), ResponseError> {
+    if model.is_gpt4() && !gateway_claims.allow_gpt4 {
+        return Err(ResponseError::Forbidden(ProxyError::Gpt4NotAllowed));
+    }
+    Ok(())
+}
+
 /// Check if the request has puid
 pub(super) fn has_puid(headers: &HeaderMap) -> Result<bool, ResponseError> {
     if let Some(hv) = headers.get(header::COOKIE) {
@@ -73,7 +166,10 @@ pub(super) fn has_puid(headers: &HeaderMap) -> Result<bool, ResponseError> {
 }
 
 /// Handle conversation request
-async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError> {
+async fn handle_conv_request(
+    req: &mut RequestExt,
+    gateway_claims: &GatewayClaims,
+) -> Result<(), ResponseError> {
     // Only handle POST request
     if !(req.uri.path().eq("/backend-api/conversation") && req.method.eq(&Method::POST)) {
         return Ok(());
@@ -105,6 +201,17 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
         .ok_or(ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?
         .to_owned();
 
+    // Transparently refresh the access token if it is about to expire and
+    // we hold a refresh token for this account, rewriting the outgoing
+    // Authorization header so the conversation request never sees a stale
+    // token.
+    let token = TokenManager::global().ensure_fresh(&token).await?;
+    req.headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(ResponseError::BadRequest)?,
+    );
+
     // If puid is exist, then return
     if !has_puid(&req.headers)? {
         // Exstract the token from the Authorization header
@@ -122,8 +229,8 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
         }
     }
 
-    let chat_requirements_token = create_chat_requirements_token(&token).await?;
-    if let Some(chat_requirements_token) = chat_requirements_token {
+    let chat_requirements = create_chat_requirements_token(&token).await?;
+    if let Some((chat_requirements_token, proofofwork)) = chat_requirements {
         req.headers.insert(
             header::HeaderName::from_static("openai-sentinel-chat-requirements-token"),
             header::HeaderValue::from_str(chat_requirements_token.as_str())
@@ -132,7 +239,21 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
         debug!(
             "Chat requirements token: {}",
             chat_requirements_token.as_str()
-        )
+        );
+
+        if let Some(proofofwork) = proofofwork.filter(|pow| pow.required) {
+            let user_agent = req
+                .headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or(EMPTY);
+            let proof_token = sentinel::solve(&proofofwork, user_agent);
+            req.headers.insert(
+                header::HeaderName::from_static("openai-sentinel-proof-token"),
+                header::HeaderValue::from_str(&proof_token).map_err(ResponseError::BadRequest)?,
+            );
+            debug!("Sentinel proof token: {}", proof_token)
+        }
     } else {
         warn!("Chat requirements token not found")
     }
@@ -140,6 +261,8 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
     // Parse model
     let model = GPTModel::from_str(model).map_err(ResponseError::BadRequest)?;
 
+    enforce_gpt4_flag(model, gateway_claims)?;
+
     // If model is gpt3 or gpt4, then add arkose_token
     if (with_context!(arkose_gpt3_experiment) && model.is_gpt3()) || model.is_gpt4() {
         let condition = match body.get(ARKOSE_TOKEN) {
@@ -187,12 +310,21 @@ async fn handle_conv_request(req: &mut RequestExt) -> Result<(), ResponseError>
 }
 
 /// Handle dashboard request
-async fn handle_dashboard_request(req: &mut RequestExt) -> Result<(), ResponseError> {
+async fn handle_dashboard_request(
+    req: &mut RequestExt,
+    gateway_claims: &GatewayClaims,
+) -> Result<(), ResponseError> {
     // Only handle POST request
     if !(req.uri.path().eq("/dashboard/user/api_keys") && req.method.eq(&Method::POST)) {
         return Ok(());
     }
 
+    // Block dashboard access for gateway tokens that weren't minted with
+    // the "allow-dashboard" flag.
+    if !gateway_claims.allow_dashboard {
+        return Err(ResponseError::Forbidden(ProxyError::DashboardNotAllowed));
+    }
+
     // Handle empty body
     let body = req
         .body
@@ -227,7 +359,12 @@ async fn handle_dashboard_request(req: &mut RequestExt) -> Result<(), ResponseEr
     Ok(())
 }
 
-async fn create_chat_requirements_token(token: &str) -> Result<Option<String>, ResponseError> {
+/// Request the sentinel chat-requirements token, returning it together with
+/// the proof-of-work challenge (if OpenAI demanded one) so the caller can
+/// solve it before dialing the conversation endpoint.
+async fn create_chat_requirements_token(
+    token: &str,
+) -> Result<Option<(String, Option<ProofOfWork>)>, ResponseError> {
     let token = token.trim_start_matches("Bearer ");
     let resp = with_context!(api_client)
         .post(format!(
@@ -243,7 +380,10 @@ async fn create_chat_requirements_token(token: &str) -> Result<Option<String>, R
     let json = serde_json::from_slice::<Value>(&body).map_err(ResponseError::BadRequest)?;
     if let Some(token_value) = json.get("token") {
         if let Some(token_str) = token_value.as_str() {
-            return Ok(Some(token_str.to_owned()));
+            let proofofwork = json
+                .get("proofofwork")
+                .and_then(|v| serde_json::from_value::<ProofOfWork>(v.clone()).ok());
+            return Ok(Some((token_str.to_owned(), proofofwork)));
         }
     }
     Ok(None)
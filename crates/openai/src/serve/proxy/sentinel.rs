@@ -0,0 +1,140 @@
+//! Sentinel proof-of-work solver for OpenAI's chat-requirements challenge.
+//!
+//! OpenAI gates `/backend-api/conversation` behind a small proof-of-work puzzle:
+//! it hands back a `seed` and a `difficulty`, and expects an
+//! `openai-sentinel-proof-token` header whose value is derived by brute-forcing
+//! a browser-fingerprint "config" blob until `sha3_512(seed + base64(config))`
+//! hexes to something lexicographically <= `difficulty`.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Local;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha3::{Digest, Sha3_512};
+
+/// Index of the iteration counter inside the fingerprint config array.
+const POW_COUNTER_INDEX: usize = 3;
+
+/// Upper bound on brute-force attempts before falling back.
+const POW_MAX_ATTEMPTS: u64 = 500_000;
+
+const POW_TOKEN_PREFIX: &str = "gAAAAAB";
+
+/// Proof-of-work parameters returned alongside the chat-requirements token.
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct ProofOfWork {
+    pub required: bool,
+    pub seed: String,
+    pub difficulty: String,
+}
+
+/// Solve the sentinel proof-of-work challenge, returning the value for the
+/// `openai-sentinel-proof-token` header.
+pub(super) fn solve(pow: &ProofOfWork, user_agent: &str) -> String {
+    let now = Local::now()
+        .format("%a %b %d %Y %H:%M:%S GMT%z (Coordinated Universal Time)")
+        .to_string();
+
+    let mut config = build_config(&now, user_agent);
+    try_solve(pow, &mut config).unwrap_or_else(|| fallback(pow))
+}
+
+/// Brute-force `config[POW_COUNTER_INDEX]` up to [`POW_MAX_ATTEMPTS`],
+/// mutating the shared config in place rather than rebuilding it (and
+/// re-boxing every `Value`) on each of the up-to-500k iterations this runs
+/// in the hot request path.
+fn try_solve(pow: &ProofOfWork, config: &mut [Value]) -> Option<String> {
+    for i in 0..POW_MAX_ATTEMPTS {
+        config[POW_COUNTER_INDEX] = json!(i);
+
+        let config_str = serde_json::to_string(config).ok()?;
+        let base64_config = BASE64.encode(config_str.as_bytes());
+        let digest_hex = digest_hex(&pow.seed, &base64_config);
+
+        if let Some(prefix) = digest_hex.get(..pow.difficulty.len()) {
+            if prefix <= pow.difficulty.as_str() {
+                return Some(format!("{POW_TOKEN_PREFIX}{base64_config}"));
+            }
+        }
+    }
+    None
+}
+
+/// Used when no solution is found in the loop budget: a constant prefix
+/// plus the reversed base64 of the seed, which OpenAI still accepts.
+fn fallback(pow: &ProofOfWork) -> String {
+    let reversed_base64: String = BASE64.encode(pow.seed.as_bytes()).chars().rev().collect();
+    format!("{POW_TOKEN_PREFIX}{reversed_base64}")
+}
+
+fn digest_hex(seed: &str, base64_config: &str) -> String {
+    let mut hasher = Sha3_512::new();
+    hasher.update(seed.as_bytes());
+    hasher.update(base64_config.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Build the browser-fingerprint config array, with a placeholder counter at
+/// [`POW_COUNTER_INDEX`] that the caller overwrites per attempt.
+fn build_config(local_date: &str, user_agent: &str) -> Vec<Value> {
+    vec![
+        json!(screen_scale()),
+        json!(local_date),
+        json!(4294705152i64),
+        json!(0),
+        json!(user_agent),
+        Value::Null,
+        Value::Null,
+        json!("location"),
+    ]
+}
+
+/// Stand-in for `screen.width * screen.height` on a typical desktop viewport.
+fn screen_scale() -> i64 {
+    3_600
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A known (seed, difficulty, date, user-agent) vector together with the
+    /// counter it solves at, precomputed independently of this
+    /// implementation. Asserting against the exact resulting token (rather
+    /// than just the shared `gAAAAAB` prefix, which the fallback path also
+    /// produces) proves a real solution was found, not a fallback.
+    #[test]
+    fn solves_a_known_vector() {
+        let pow = ProofOfWork {
+            required: true,
+            seed: "test-seed-vector".to_owned(),
+            difficulty: "0".to_owned(),
+        };
+        let date = "Sun Jul 26 2026 00:00:00 GMT+0000 (Coordinated Universal Time)";
+        let mut config = build_config(date, "ninja-test-agent/1.0");
+
+        let token = try_solve(&pow, &mut config).expect("vector is solvable within the budget");
+
+        assert_eq!(
+            token,
+            format!(
+                "{POW_TOKEN_PREFIX}WzM2MDAsIlN1biBKdWwgMjYgMjAyNiAwMDowMDowMCBHTVQrMDAwMCAoQ29vcmRpbmF0ZWQgVW5pdmVyc2FsIFRpbWUpIiw0Mjk0NzA1MTUyLDEsIm5pbmphLXRlc3QtYWdlbnQvMS4wIixudWxsLG51bGwsImxvY2F0aW9uIl0="
+            )
+        );
+        // The solving counter (1) landed at the index the hot loop mutates.
+        assert_eq!(config[POW_COUNTER_INDEX], json!(1));
+    }
+
+    #[test]
+    fn falls_back_when_unsolvable() {
+        let pow = ProofOfWork {
+            required: true,
+            seed: "unsolvable-seed".to_owned(),
+            difficulty: "00000000".to_owned(),
+        };
+        let token = solve(&pow, "ninja-test-agent/1.0");
+        let reversed_base64: String = BASE64.encode(pow.seed.as_bytes()).chars().rev().collect();
+        assert_eq!(token, format!("{POW_TOKEN_PREFIX}{reversed_base64}"));
+    }
+}
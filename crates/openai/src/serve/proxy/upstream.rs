@@ -0,0 +1,167 @@
+//! A small pool of upstream ChatGPT-API mirrors with health tracking and
+//! failover, so a single misbehaving frontend doesn't take the proxy down
+//! with it.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use reqwest::Proxy;
+use serde::Deserialize;
+
+/// How long an upstream is skipped after it is marked unhealthy.
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// One configured upstream mirror.
+///
+/// `origin` is owned rather than `&'static str`: these are deserialized at
+/// runtime from operator config, and serde's borrowed-`&str` support can
+/// only ever borrow from the input buffer, not manufacture a `'static` one.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamConfig {
+    /// Base URL, e.g. `https://chat.openai.com`.
+    pub origin: String,
+    /// Optional proxy used only for requests to this upstream.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Relative weight used by [`SelectionMode::Weighted`]. Ignored by
+    /// round-robin.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// How [`UpstreamPool::select`] picks among the healthy upstreams.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum SelectionMode {
+    #[default]
+    RoundRobin,
+    Weighted,
+}
+
+struct UpstreamState {
+    config: UpstreamConfig,
+    unhealthy_until: Mutex<Option<Instant>>,
+    /// Client dialing through this upstream's configured proxy, if any. The
+    /// caller falls back to the shared client when this is `None`.
+    client: Option<reqwest::Client>,
+}
+
+impl UpstreamState {
+    fn new(config: UpstreamConfig) -> Self {
+        let client = config
+            .proxy
+            .as_deref()
+            .and_then(|proxy_url| Proxy::all(proxy_url).ok())
+            .and_then(|proxy| reqwest::Client::builder().proxy(proxy).build().ok());
+        Self {
+            config,
+            unhealthy_until: Mutex::new(None),
+            client,
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        match *self.unhealthy_until.lock() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    fn mark_unhealthy(&self) {
+        *self.unhealthy_until.lock() = Some(Instant::now() + UNHEALTHY_COOLDOWN);
+    }
+}
+
+/// A config-driven pool of upstream mirrors, selected round-robin or by
+/// weight, with automatic failover away from unhealthy members.
+pub struct UpstreamPool {
+    upstreams: Vec<UpstreamState>,
+    mode: SelectionMode,
+    cursor: AtomicUsize,
+}
+
+impl UpstreamPool {
+    pub fn new(configs: Vec<UpstreamConfig>, mode: SelectionMode) -> Self {
+        let upstreams = configs.into_iter().map(UpstreamState::new).collect();
+        Self {
+            upstreams,
+            mode,
+            cursor: AtomicUsize::new(0),
+        }
+    }
+
+    /// Select the next upstream to try, skipping any still in their cooldown
+    /// window. Returns `None` if every upstream is currently unhealthy.
+    pub fn select(&self) -> Option<&UpstreamConfig> {
+        let healthy: Vec<&UpstreamState> =
+            self.upstreams.iter().filter(|u| u.is_healthy()).collect();
+        if healthy.is_empty() {
+            return None;
+        }
+
+        let state = match self.mode {
+            SelectionMode::RoundRobin => {
+                let idx = self.cursor.fetch_add(1, Ordering::Relaxed) % healthy.len();
+                healthy[idx]
+            }
+            SelectionMode::Weighted => {
+                let total_weight: u32 = healthy.iter().map(|u| u.config.weight.max(1)).sum();
+                let mut pick = (self.cursor.fetch_add(1, Ordering::Relaxed) as u32) % total_weight;
+                let mut chosen = healthy[0];
+                for u in &healthy {
+                    let weight = u.config.weight.max(1);
+                    if pick < weight {
+                        chosen = u;
+                        break;
+                    }
+                    pick -= weight;
+                }
+                chosen
+            }
+        };
+        Some(&state.config)
+    }
+
+    /// Mark `origin` unhealthy for [`UNHEALTHY_COOLDOWN`], so subsequent
+    /// [`select`](Self::select) calls skip it until the cooldown lapses.
+    pub fn mark_unhealthy(&self, origin: &str) {
+        if let Some(state) = self.upstreams.iter().find(|u| u.config.origin == origin) {
+            state.mark_unhealthy();
+        }
+    }
+
+    /// All currently healthy upstreams other than `exclude`, in configuration
+    /// order, for sequential failover once the first pick has failed.
+    pub fn fallbacks(&self, exclude: &str) -> Vec<&UpstreamConfig> {
+        self.upstreams
+            .iter()
+            .filter(|u| u.is_healthy() && u.config.origin != exclude)
+            .map(|u| &u.config)
+            .collect()
+    }
+
+    /// The client that should be used to dial `origin`: a client built with
+    /// that upstream's configured proxy, or `None` when it has none (the
+    /// caller should fall back to the shared client in that case).
+    pub fn client_for(&self, origin: &str) -> Option<reqwest::Client> {
+        self.upstreams
+            .iter()
+            .find(|u| u.config.origin == origin)
+            .and_then(|u| u.client.clone())
+    }
+}
+
+/// Whether a response indicates the upstream should be marked unhealthy:
+/// a server error, or OpenAI's Cloudflare challenge page. Only looks at the
+/// status and headers so callers can decide this before buffering (and
+/// potentially breaking) a streamed body.
+pub fn is_failover_response(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap) -> bool {
+    if status.is_server_error() {
+        return true;
+    }
+    status == reqwest::StatusCode::FORBIDDEN && headers.contains_key("cf-mitigated")
+}
@@ -0,0 +1,187 @@
+//! Transparent access-token refresh, in the spirit of fxa_client/Zed's
+//! mint-then-refresh flow: decode the bearer token's `exp`, and if it is
+//! about to lapse and we hold a refresh token for that account, mint a new
+//! access token before the caller's request ever reaches OpenAI.
+
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::serve::error::{ProxyError, ResponseError};
+use crate::serve::puid::reduce_key;
+use crate::with_context;
+
+/// Refresh a token this far ahead of its expiry so an in-flight request
+/// doesn't race the deadline.
+const REFRESH_SKEW_SECS: i64 = 60;
+
+/// OpenAI's OAuth token endpoint (auth0), used for the `refresh_token` grant.
+/// This is the same endpoint and public client the official clients use to
+/// refresh a session — not the `backend-api` surface, which has no refresh
+/// route of its own.
+const OPENAI_OAUTH_TOKEN_URL: &str = "https://auth0.openai.com/oauth/token";
+
+/// Public OAuth client id used by ChatGPT's own web/desktop clients.
+const OPENAI_OAUTH_CLIENT_ID: &str = "pdlLIX2Y72MIl2rhLhTE9VV9bN905kBh";
+
+static TOKEN_MANAGER: Lazy<TokenManager> = Lazy::new(TokenManager::default);
+
+/// OAuth refresh tokens on file, keyed by [`reduce_key`]. Populated by the
+/// auth flow when an account first authenticates; looked up through
+/// `with_context!(refresh_token_store)` rather than owned by
+/// [`TokenManager`] so it can be persisted/restored independently of the
+/// in-memory access-token cache.
+pub type RefreshTokenStore = DashMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    exp: i64,
+}
+
+#[derive(Clone)]
+struct CachedToken {
+    access_token: String,
+    exp: i64,
+}
+
+/// Caches refreshed access tokens keyed by [`reduce_key`], with a per-key
+/// lock so concurrent conversation requests for one account only trigger a
+/// single refresh call.
+#[derive(Default)]
+pub struct TokenManager {
+    cache: DashMap<String, CachedToken>,
+    /// Per-key single-flight locks. Entries are removed once the refresh
+    /// attempt that created them finishes, so this stays bounded by the
+    /// number of refreshes currently in flight rather than growing one
+    /// entry per account for the life of the process.
+    locks: DashMap<String, Arc<Mutex<()>>>,
+}
+
+impl TokenManager {
+    pub fn global() -> &'static TokenManager {
+        &TOKEN_MANAGER
+    }
+
+    /// Return the access token to use for `bearer_token`, refreshing it
+    /// first if it is about to expire and a refresh token is on file.
+    /// Without a refresh token on file, `bearer_token` is returned unchanged
+    /// even if it has already expired.
+    pub async fn ensure_fresh(&self, bearer_token: &str) -> Result<String, ResponseError> {
+        let exp = match decode_exp(bearer_token) {
+            Some(exp) => exp,
+            // Not a JWT we can introspect: pass it through unchanged.
+            None => return Ok(bearer_token.to_owned()),
+        };
+
+        if !is_near_expiry(exp) {
+            return Ok(bearer_token.to_owned());
+        }
+
+        let cache_id = reduce_key(bearer_token)?;
+
+        if let Some(cached) = self.cache.get(&cache_id) {
+            if !is_near_expiry(cached.exp) {
+                return Ok(cached.access_token.clone());
+            }
+        }
+
+        let lock = self
+            .locks
+            .entry(cache_id.clone())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone();
+        let result = {
+            let _guard = lock.lock().await;
+            self.refresh_locked(&cache_id, bearer_token).await
+        };
+        // Bound the lock map to refreshes currently in flight rather than
+        // one entry per account forever.
+        self.locks.remove(&cache_id);
+        result
+    }
+
+    /// Does the actual refresh-or-reuse work once the per-account lock is
+    /// held; assumes the caller has already checked the unlocked cache.
+    async fn refresh_locked(
+        &self,
+        cache_id: &str,
+        bearer_token: &str,
+    ) -> Result<String, ResponseError> {
+        // Re-check after acquiring the lock: another caller may have
+        // already refreshed while we were waiting.
+        if let Some(cached) = self.cache.get(cache_id) {
+            if !is_near_expiry(cached.exp) {
+                return Ok(cached.access_token.clone());
+            }
+        }
+
+        let Some(refresh_token) = with_context!(refresh_token_store)
+            .and_then(|store| store.get(cache_id).map(|entry| entry.value().clone()))
+        else {
+            // No refresh token on file for this account; let the caller's
+            // token stand and fail upstream if it really has expired.
+            return Ok(bearer_token.to_owned());
+        };
+
+        let refreshed = request_refresh(&refresh_token).await?;
+        let exp = decode_exp(&refreshed).unwrap_or(0);
+        self.cache.insert(
+            cache_id.to_owned(),
+            CachedToken {
+                access_token: refreshed.clone(),
+                exp,
+            },
+        );
+
+        Ok(refreshed)
+    }
+}
+
+fn is_near_expiry(exp: i64) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    exp - now <= REFRESH_SKEW_SECS
+}
+
+/// Decode the `exp` claim out of a JWT without verifying its signature; we
+/// only need to know when OpenAI's own token says it expires.
+fn decode_exp(token: &str) -> Option<i64> {
+    let token = token.trim_start_matches("Bearer ").trim();
+    let payload = token.split('.').nth(1)?;
+    let decoded = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    serde_json::from_slice::<Claims>(&decoded).ok().map(|c| c.exp)
+}
+
+async fn request_refresh(refresh_token: &str) -> Result<String, ResponseError> {
+    #[derive(Deserialize)]
+    struct RefreshResponse {
+        access_token: String,
+    }
+
+    let resp = with_context!(api_client)
+        .post(OPENAI_OAUTH_TOKEN_URL)
+        .json(&serde_json::json!({
+            "client_id": OPENAI_OAUTH_CLIENT_ID,
+            "grant_type": "refresh_token",
+            "refresh_token": refresh_token,
+        }))
+        .send()
+        .await
+        .map_err(ResponseError::InternalServerError)?
+        .error_for_status()
+        .map_err(|_| ResponseError::Unauthorized(ProxyError::AccessTokenRequired))?;
+
+    let body: RefreshResponse = resp
+        .json()
+        .await
+        .map_err(ResponseError::InternalServerError)?;
+    Ok(body.access_token)
+}
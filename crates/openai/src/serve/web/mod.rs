@@ -0,0 +1,186 @@
+//! Built-in OpenAI-compatible streaming playground and arena.
+//!
+//! Mirrors aichat's `serve.rs`: a couple of embedded HTML pages served
+//! straight from the binary, backed by SSE routes that stream tokens from
+//! `toapi::send_request` without buffering the whole completion first.
+
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use axum::extract::Query;
+use axum::http::header;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::stream::{self, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use strum::IntoEnumIterator;
+
+use crate::gpt_model::GPTModel;
+use crate::serve::error::ResponseError;
+use crate::serve::proxy::ext::RequestExt;
+use crate::serve::proxy::toapi;
+
+const PLAYGROUND_HTML: &str = include_str!("playground.html");
+const ARENA_HTML: &str = include_str!("arena.html");
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/playground", get(playground_page))
+        .route("/arena", get(arena_page))
+        .route("/playground/models", get(list_models))
+        .route("/playground/stream", post(playground_stream))
+        .route("/arena/stream", get(arena_stream))
+}
+
+async fn playground_page() -> Html<&'static str> {
+    Html(PLAYGROUND_HTML)
+}
+
+async fn arena_page() -> Html<&'static str> {
+    Html(ARENA_HTML)
+}
+
+async fn list_models() -> Json<Vec<String>> {
+    Json(GPTModel::iter().map(|m| m.to_string()).collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaygroundRequest {
+    model: String,
+    prompt: String,
+}
+
+/// Stream a single model's tokens back over `text/event-stream`.
+async fn playground_stream(
+    Json(body): Json<PlaygroundRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ResponseError> {
+    let model = GPTModel::from_str(&body.model).map_err(ResponseError::BadRequest)?;
+    let resp = toapi::send_request(chat_completions_request(model, &body.prompt)?).await?;
+
+    let stream = decode_sse_deltas(resp.into_byte_stream())
+        .map(|text| Ok(Event::default().data(text)));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[derive(Debug, Deserialize)]
+struct ArenaQuery {
+    body: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArenaRequest {
+    model_a: String,
+    model_b: String,
+    prompt: String,
+}
+
+/// Fan one prompt out to two models and stream both columns concurrently,
+/// tagging each SSE event with the column (`a` or `b`) it belongs to.
+async fn arena_stream(
+    Query(query): Query<ArenaQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ResponseError> {
+    let body: ArenaRequest = serde_json::from_str(&query.body).map_err(ResponseError::BadRequest)?;
+
+    let model_a = GPTModel::from_str(&body.model_a).map_err(ResponseError::BadRequest)?;
+    let model_b = GPTModel::from_str(&body.model_b).map_err(ResponseError::BadRequest)?;
+
+    let req_a = chat_completions_request(model_a, &body.prompt)?;
+    let req_b = chat_completions_request(model_b, &body.prompt)?;
+
+    // Kick off both upstream legs concurrently rather than awaiting the
+    // first in full before starting the second.
+    let (resp_a, resp_b) = tokio::try_join!(
+        toapi::send_request(req_a),
+        toapi::send_request(req_b),
+    )?;
+
+    let stream_a = decode_sse_deltas(resp_a.into_byte_stream())
+        .map(|text| Ok(Event::default().event("a").data(text)));
+    let stream_b = decode_sse_deltas(resp_b.into_byte_stream())
+        .map(|text| Ok(Event::default().event("b").data(text)));
+
+    let merged = stream::select(stream_a, stream_b).chain(stream::once(async {
+        Ok(Event::default().event("done").data(""))
+    }));
+
+    Ok(Sse::new(merged).keep_alive(KeepAlive::default()))
+}
+
+/// Turn a raw upstream byte stream of `chat.completion.chunk` SSE frames
+/// into the decoded assistant token text, buffering across chunk
+/// boundaries so a frame split across two reads still parses.
+fn decode_sse_deltas(
+    byte_stream: impl Stream<Item = reqwest::Result<bytes::Bytes>> + Send + 'static,
+) -> impl Stream<Item = String> + Send + 'static {
+    stream::unfold(
+        (Box::pin(byte_stream), String::new()),
+        |(mut byte_stream, mut buf)| async move {
+            loop {
+                if let Some(pos) = buf.find("\n\n") {
+                    let frame = buf[..pos].to_owned();
+                    buf.drain(..pos + 2);
+                    if let Some(text) = parse_sse_frame(&frame) {
+                        return Some((text, (byte_stream, buf)));
+                    }
+                    continue;
+                }
+
+                match byte_stream.next().await {
+                    Some(Ok(chunk)) => buf.push_str(&String::from_utf8_lossy(&chunk)),
+                    Some(Err(_)) | None => return None,
+                }
+            }
+        },
+    )
+}
+
+/// Extract the `choices[0].delta.content` text out of one SSE frame's
+/// `data:` lines, ignoring the `[DONE]` sentinel and anything that doesn't
+/// parse as a chat-completion chunk.
+fn parse_sse_frame(frame: &str) -> Option<String> {
+    let mut text = String::new();
+    for line in frame.lines() {
+        let Some(data) = line
+            .strip_prefix("data: ")
+            .or_else(|| line.strip_prefix("data:"))
+        else {
+            continue;
+        };
+        let data = data.trim();
+        if data.is_empty() || data == "[DONE]" {
+            continue;
+        }
+        if let Ok(value) = serde_json::from_str::<Value>(data) {
+            if let Some(delta) = value
+                .get("choices")
+                .and_then(|c| c.get(0))
+                .and_then(|c| c.get("delta"))
+                .and_then(|d| d.get("content"))
+                .and_then(|c| c.as_str())
+            {
+                text.push_str(delta);
+            }
+        }
+    }
+    (!text.is_empty()).then_some(text)
+}
+
+fn chat_completions_request(model: GPTModel, prompt: &str) -> Result<RequestExt, ResponseError> {
+    let body = serde_json::json!({
+        "model": model.to_string(),
+        "messages": [{ "role": "user", "content": prompt }],
+        "stream": true,
+    });
+    RequestExt::builder()
+        .method(axum::http::Method::POST)
+        .uri("/v1/chat/completions".parse().map_err(ResponseError::BadRequest)?)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(bytes::Bytes::from(
+            serde_json::to_vec(&body).map_err(ResponseError::BadRequest)?,
+        ))
+        .build()
+        .map_err(ResponseError::BadRequest)
+}